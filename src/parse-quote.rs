@@ -1,13 +1,19 @@
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{self, Write};
+use std::net::Ipv4Addr;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::cmp::Reverse;
+use bytes::{Buf, BytesMut};
 use clap::{Command, Arg};
+use memmap2::Mmap;
 use pcap::Capture;
 use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
 use pnet::packet::ip::IpNextHeaderProtocols;
 use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::tcp::TcpPacket;
 use pnet::packet::Packet;
 use pnet::packet::udp::UdpPacket;
+use tokio_util::codec::Decoder;
 
 #[derive(Debug)]
 struct QuoteMessage {
@@ -22,9 +28,259 @@ struct QuoteMessage {
 enum OutputFormat {
     Default,
     Formatted,
+    /// Fixed-width little-endian SBE-style binary records, see `write_binary_message`.
+    Binary,
+    /// One row per quote with a header, see `write_csv_row`.
+    Csv,
 }
 
-fn parse_quote_message(
+const CSV_HEADER: &str = "packet_time,accept_time,issue_code,\
+bid1_qty,bid1_px,bid2_qty,bid2_px,bid3_qty,bid3_px,bid4_qty,bid4_px,bid5_qty,bid5_px,\
+ask1_qty,ask1_px,ask2_qty,ask2_px,ask3_qty,ask3_px,ask4_qty,ask4_px,ask5_qty,ask5_px";
+
+/// Writes one CSV row for `msg`. Bid/ask levels are zero-filled out to five
+/// so every row has the same column count, in the same bid-descending /
+/// ask-ascending order as the text formatter.
+fn write_csv_row<W: Write>(w: &mut W, msg: &QuoteMessage) -> io::Result<()> {
+    write!(
+        w,
+        "{},{},{}",
+        format_system_time(msg.packet_time),
+        format_system_time(msg.accept_time),
+        msg.issue_code.trim()
+    )?;
+
+    for i in 0..5 {
+        let (qty, price) = msg.bids.iter().rev().nth(i).copied().unwrap_or((0, 0));
+        write!(w, ",{},{}", qty, price)?;
+    }
+    for i in 0..5 {
+        let (qty, price) = msg.asks.get(i).copied().unwrap_or((0, 0));
+        write!(w, ",{},{}", qty, price)?;
+    }
+    writeln!(w)
+}
+
+// Binary (--format sbe) stream layout.
+const SBE_MAGIC: &[u8; 4] = b"SBEQ";
+const SBE_VERSION: u32 = 1;
+const SBE_RECORD_SIZE: u32 = 188; // 12 (issue_code) + 8 + 8 (times) + 10 * 16 (bid/ask pairs)
+
+/// Writes the 16-byte stream header: magic, version, record size, reserved.
+fn write_binary_header<W: Write>(w: &mut W) -> io::Result<()> {
+    w.write_all(SBE_MAGIC)?;
+    w.write_all(&SBE_VERSION.to_le_bytes())?;
+    w.write_all(&SBE_RECORD_SIZE.to_le_bytes())?;
+    w.write_all(&0u32.to_le_bytes())?;
+    Ok(())
+}
+
+/// Writes one fixed-width binary record for `msg`: a 12-byte space-padded
+/// issue code, `packet_time`/`accept_time` as u64 microseconds-since-epoch,
+/// then five bid `(qty, price)` pairs (bid-descending) followed by five ask
+/// pairs (ask-ascending), matching the text formatter's level order.
+fn write_binary_message<W: Write>(w: &mut W, msg: &QuoteMessage) -> io::Result<()> {
+    let mut issue_code = msg.issue_code.trim().as_bytes().to_vec();
+    issue_code.resize(12, b' ');
+    issue_code.truncate(12);
+    w.write_all(&issue_code)?;
+
+    w.write_all(&system_time_micros(msg.packet_time).to_le_bytes())?;
+    w.write_all(&system_time_micros(msg.accept_time).to_le_bytes())?;
+
+    for &(qty, price) in msg.bids.iter().rev() {
+        w.write_all(&qty.to_le_bytes())?;
+        w.write_all(&price.to_le_bytes())?;
+    }
+    for &(qty, price) in msg.asks.iter() {
+        w.write_all(&qty.to_le_bytes())?;
+        w.write_all(&price.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn system_time_micros(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
+}
+
+/// Running per-issue aggregates for `--summarize` mode.
+#[derive(Debug)]
+struct IssueStats {
+    message_count: u64,
+    first_accept_time: SystemTime,
+    last_accept_time: SystemTime,
+    highest_bid: u64,
+    lowest_ask: u64,
+    cross_count: u64,
+}
+
+impl IssueStats {
+    fn new(msg: &QuoteMessage) -> Self {
+        let (best_bid, best_ask) = top_of_book(msg);
+        IssueStats {
+            message_count: 1,
+            first_accept_time: msg.accept_time,
+            last_accept_time: msg.accept_time,
+            highest_bid: best_bid,
+            lowest_ask: best_ask,
+            cross_count: touches_or_crosses(best_bid, best_ask) as u64,
+        }
+    }
+
+    fn update(&mut self, msg: &QuoteMessage) {
+        let (best_bid, best_ask) = top_of_book(msg);
+        self.message_count += 1;
+        self.last_accept_time = msg.accept_time;
+        self.highest_bid = self.highest_bid.max(best_bid);
+        if best_ask > 0 {
+            self.lowest_ask = if self.lowest_ask == 0 {
+                best_ask
+            } else {
+                self.lowest_ask.min(best_ask)
+            };
+        }
+        if touches_or_crosses(best_bid, best_ask) {
+            self.cross_count += 1;
+        }
+    }
+}
+
+/// Best bid/ask prices for `msg`. The parser stores bids ascending (best
+/// bid last) and asks ascending (best ask first), per the same ordering the
+/// text formatter relies on.
+fn top_of_book(msg: &QuoteMessage) -> (u64, u64) {
+    let best_bid = msg.bids.last().map(|&(_, price)| price).unwrap_or(0);
+    let best_ask = msg.asks.first().map(|&(_, price)| price).unwrap_or(0);
+    (best_bid, best_ask)
+}
+
+fn touches_or_crosses(best_bid: u64, best_ask: u64) -> bool {
+    best_ask > 0 && best_bid >= best_ask
+}
+
+/// Prints one summary line per issue, most active issues first.
+fn print_issue_summary(stats: &HashMap<String, IssueStats>) {
+    let mut rows: Vec<(&String, &IssueStats)> = stats.iter().collect();
+    rows.sort_by_key(|(_, s)| Reverse(s.message_count));
+
+    for (issue_code, s) in rows {
+        println!(
+            "{} messages={} first={} last={} highest_bid={} lowest_ask={} crosses={}",
+            issue_code.trim(),
+            s.message_count,
+            format_system_time(s.first_accept_time),
+            format_system_time(s.last_accept_time),
+            s.highest_bid,
+            s.lowest_ask,
+            s.cross_count,
+        );
+    }
+}
+
+/// Fixed length of a B6034 quote message, marker through accept time.
+const QUOTE_MESSAGE_LEN: usize = 214;
+
+/// Upper bound on a single TCP flow's reassembly backlog in `CaptureState`.
+/// Segments are appended in arrival order (sequence numbers are not checked),
+/// so this is room for 32 messages' worth of bytes; past it the flow is
+/// assumed stuck on a stalled or malformed connection and its buffered bytes
+/// are cleared.
+const MAX_TCP_STREAM_BUFFER: usize = 32 * QUOTE_MESSAGE_LEN;
+
+/// Upper bound on the number of concurrent TCP flows tracked in
+/// `CaptureState::tcp_streams`. Reaching this bound evicts the
+/// least-recently-touched flow rather than refusing new ones, so a
+/// long-running `--device` capture keeps making room for new connections
+/// instead of wedging once it has ever seen `MAX_TCP_STREAM_FLOWS` 4-tuples.
+const MAX_TCP_STREAM_FLOWS: usize = 4096;
+
+/// A TCP flow's reassembly backlog plus the `CaptureState::tcp_stream_clock`
+/// tick it was last appended to, used to find the least-recently-touched
+/// flow to evict once `CaptureState::tcp_streams` is at capacity.
+#[derive(Default)]
+struct TcpFlowBuffer {
+    data: BytesMut,
+    last_touched: u64,
+}
+
+/// Evicts the least-recently-touched flow from `streams`, making room for a
+/// new one. `streams` is expected to be at capacity when this is called, so
+/// there's always at least one entry to evict.
+fn evict_oldest_tcp_flow(streams: &mut HashMap<(Ipv4Addr, u16, Ipv4Addr, u16), TcpFlowBuffer>) {
+    if let Some(&oldest_key) = streams
+        .iter()
+        .min_by_key(|(_, flow)| flow.last_touched)
+        .map(|(key, _)| key)
+    {
+        streams.remove(&oldest_key);
+    }
+}
+
+/// Frames B6034 quote messages out of an arbitrary byte stream: scans for
+/// the marker, waits for a full fixed-length message to arrive, then hands
+/// off to the same field parser the PCAP loop uses.
+struct QuoteDecoder {
+    // Fixed packet_time to stamp every decoded message with, used when
+    // wrapping a single already-timestamped packet (see `parse_quote_message`).
+    // `None` means "stamp with the time of decoding", for genuine async streams.
+    fixed_packet_time: Option<SystemTime>,
+}
+
+impl QuoteDecoder {
+    fn with_packet_time(packet_time: SystemTime) -> Self {
+        QuoteDecoder { fixed_packet_time: Some(packet_time) }
+    }
+}
+
+impl Default for QuoteDecoder {
+    /// Stamps each decoded message with the time it was decoded; the right
+    /// default for a genuine async stream with no per-packet capture time.
+    fn default() -> Self {
+        QuoteDecoder { fixed_packet_time: None }
+    }
+}
+
+impl Decoder for QuoteDecoder {
+    type Item = QuoteMessage;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<QuoteMessage>, io::Error> {
+        let marker_pos = match src.windows(5).position(|w| w == b"B6034") {
+            Some(pos) => pos,
+            None => {
+                // No marker yet; keep the last few bytes in case it's split
+                // across reads, discard the rest.
+                if src.len() > 4 {
+                    let keep_from = src.len() - 4;
+                    src.advance(keep_from);
+                }
+                return Ok(None);
+            }
+        };
+
+        if src.len() < marker_pos + QUOTE_MESSAGE_LEN {
+            // Full message isn't buffered yet; wait for more data.
+            return Ok(None);
+        }
+
+        src.advance(marker_pos);
+        let frame = src.split_to(QUOTE_MESSAGE_LEN);
+        let packet_time = self.fixed_packet_time.unwrap_or_else(SystemTime::now);
+        Ok(parse_quote_fields(&frame, packet_time))
+    }
+}
+
+/// Parses a single already-framed B6034 message. Thin wrapper around
+/// `QuoteDecoder` for callers (like the PCAP loop) that hand over one
+/// packet at a time with its own timestamp rather than an async byte stream.
+fn parse_quote_message(packet_data: &[u8], packet_time: SystemTime) -> Option<QuoteMessage> {
+    let mut buf = BytesMut::from(packet_data);
+    QuoteDecoder::with_packet_time(packet_time)
+        .decode(&mut buf)
+        .unwrap_or(None)
+}
+
+fn parse_quote_fields(
     packet_data: &[u8],
     packet_time: SystemTime,
 ) -> Option<QuoteMessage> {
@@ -151,17 +407,49 @@ fn parse_accept_time(s: &str, packet_time: SystemTime) -> Option<SystemTime> {
     Some(packet_midnight + Duration::from_secs(hour * 3600 + min * 60 + sec) + Duration::from_micros(micros))
 }
 
+/// Parses a human duration string such as `3s`, `500ms`, or `250us` into a
+/// `Duration`. An integer with no suffix is treated as whole seconds.
+fn parse_reorder_window(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (digits, unit) = if let Some(stripped) = s.strip_suffix("us") {
+        (stripped, "us")
+    } else if let Some(stripped) = s.strip_suffix("ms") {
+        (stripped, "ms")
+    } else if let Some(stripped) = s.strip_suffix('s') {
+        (stripped, "s")
+    } else {
+        (s, "s")
+    };
+
+    let value = digits
+        .parse::<u64>()
+        .map_err(|_| format!("invalid --reorder-window '{}': expected an integer optionally suffixed with 's', 'ms', or 'us'", s))?;
+
+    Ok(match unit {
+        "us" => Duration::from_micros(value),
+        "ms" => Duration::from_millis(value),
+        _ => Duration::from_secs(value),
+    })
+}
+
 fn main() {
     let matches = Command::new("parse-quote")
         .version("1.0")
         .author("Market Data Parser")
-        .about("Parses KOSPI200 market data from PCAP files")
+        .about("Parses KOSPI200 market data from PCAP files or a live network device")
         .arg(
             Arg::new("input")
                 .help("Input PCAP file")
-                .required(true)
+                .required_unless_present("device")
                 .index(1),
         )
+        .arg(
+            Arg::new("device")
+                .long("device")
+                .help("Capture live from this network interface instead of reading a PCAP file")
+                .takes_value(true)
+                .conflicts_with("input"),
+        )
         .arg(
             Arg::new("reorder")
                 .short('r')
@@ -169,6 +457,12 @@ fn main() {
                 .help("Reorder messages by accept time")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("reorder-window")
+                .long("reorder-window")
+                .help("Reorder watermark as a duration (e.g. 3s, 500ms, 250us); default 3s")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("output")
                 .short('o')
@@ -176,124 +470,405 @@ fn main() {
                 .help("Use formatted output")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("Output format: default, formatted, sbe (fixed-width binary), or csv")
+                .takes_value(true)
+                .possible_values(["default", "formatted", "sbe", "csv"]),
+        )
+        .arg(
+            Arg::new("summarize")
+                .long("summarize")
+                .help("Aggregate per-issue stats instead of printing every quote")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .help("Destination port to capture (repeatable); defaults to 15515 and 15516")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("mmap")
+                .long("mmap")
+                .help("Read the input PCAP file through a memory map instead of pcap::Capture")
+                .takes_value(false)
+                .conflicts_with("device"),
+        )
         .get_matches();
 
-    let input_file = matches.value_of("input").unwrap();
     let reorder = matches.is_present("reorder");
-    let output_format = if matches.is_present("output") {
-        OutputFormat::Formatted
-    } else {
-        OutputFormat::Default
+    let summarize = matches.is_present("summarize");
+    let output_format = match matches.value_of("format") {
+        Some("sbe") => OutputFormat::Binary,
+        Some("csv") => OutputFormat::Csv,
+        Some("formatted") => OutputFormat::Formatted,
+        Some("default") | None if matches.is_present("output") => OutputFormat::Formatted,
+        Some("default") | None => OutputFormat::Default,
+        Some(_) => unreachable!("clap enforces possible_values"),
     };
-    
-    // Open the pcap file
-    let mut cap = Capture::from_file(input_file).expect("Failed to open pcap file");
-    
-    // Create a binary heap to store messages sorted by accept_time
-    let mut message_buffer: BinaryHeap<Reverse<QuoteMessage>> = BinaryHeap::new();
-    let mut latest_packet_time = None;
+    let reorder_window = match matches.value_of("reorder-window") {
+        Some(s) => parse_reorder_window(s).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }),
+        None => Duration::from_secs(3),
+    };
+    let ports: Vec<u16> = match matches.values_of("port") {
+        Some(values) => values
+            .map(|v| {
+                v.parse::<u16>().unwrap_or_else(|_| {
+                    eprintln!("error: invalid --port '{}': expected a number from 0-65535", v);
+                    std::process::exit(1);
+                })
+            })
+            .collect(),
+        None => vec![15515, 15516],
+    };
+
+    let config = CaptureConfig {
+        ports,
+        reorder,
+        reorder_window,
+        output_format,
+        summarize,
+    };
+
+    if let Some(device) = matches.value_of("device") {
+        // Live feeds never hit EOF, so the capture loop below runs until the
+        // device errors out (e.g. the interface goes down).
+        let mut cap = Capture::from_device(device)
+            .expect("Failed to open capture device")
+            .promisc(true)
+            .immediate_mode(true)
+            .open()
+            .expect("Failed to activate live capture");
+        run_capture(&mut cap, &config, true);
+    } else {
+        let input_file = matches.value_of("input").unwrap();
+        if matches.is_present("mmap") {
+            run_capture_mmap(input_file, &config);
+        } else {
+            let mut cap = Capture::from_file(input_file).expect("Failed to open pcap file");
+            run_capture(&mut cap, &config, false);
+        }
+    }
+}
+
+/// Options that shape how captured quotes are filtered, ordered, and emitted;
+/// shared by the `pcap::Capture` loop and the `--mmap` replay path.
+struct CaptureConfig {
+    ports: Vec<u16>,
+    reorder: bool,
+    reorder_window: Duration,
+    output_format: OutputFormat,
+    summarize: bool,
+}
+
+/// Mutable aggregation state threaded through a capture run: the reorder
+/// buffer, per-issue summary stats, and in-flight TCP stream reassembly
+/// buffers keyed by 4-tuple.
+#[derive(Default)]
+struct CaptureState {
+    message_buffer: BinaryHeap<Reverse<QuoteMessage>>,
+    latest_packet_time: Option<SystemTime>,
+    issue_stats: HashMap<String, IssueStats>,
+    tcp_streams: HashMap<(Ipv4Addr, u16, Ipv4Addr, u16), TcpFlowBuffer>,
+    /// Monotonic tick bumped once per TCP segment, stamped onto the flow it
+    /// belongs to so `evict_oldest_tcp_flow` can find the least-recently-used
+    /// entry when `tcp_streams` is full.
+    tcp_stream_clock: u64,
+}
+
+fn write_format_header<W: Write>(out: &mut W, output_format: OutputFormat) {
+    match output_format {
+        OutputFormat::Binary => {
+            write_binary_header(out).expect("Failed to write SBE stream header");
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "{}", CSV_HEADER).expect("Failed to write CSV header");
+        }
+        OutputFormat::Default | OutputFormat::Formatted => {}
+    }
+}
+
+fn run_capture<T: pcap::Activated>(cap: &mut Capture<T>, config: &CaptureConfig, live: bool) {
+    // Quote dumps are large; buffer output instead of a println! per message.
+    let mut out = io::BufWriter::new(io::stdout());
+    let mut state = CaptureState::default();
+
+    if !config.summarize {
+        write_format_header(&mut out, config.output_format);
+    }
 
     while let Ok(packet) = cap.next_packet() {
-        if let Some(ethernet) = EthernetPacket::new(packet.data) {
-            if ethernet.get_ethertype() == EtherTypes::Ipv4 {
-                if let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) {
-                    if ipv4.get_next_level_protocol() == IpNextHeaderProtocols::Udp {
-                        if let Some(udp_packet) = UdpPacket::new(ipv4.payload()) {
-                            let dst_port = udp_packet.get_destination();
-
-                            if dst_port == 15515 || dst_port == 15516 {
-                                let udp_payload = udp_packet.payload();
-                                let packet_time = SystemTime::UNIX_EPOCH + 
-                                    Duration::from_secs(packet.header.ts.tv_sec as u64) +
-                                    Duration::from_micros(packet.header.ts.tv_usec as u64);
-                                    
-                                if let Some(msg) = parse_quote_message(udp_payload, packet_time) {
-                                    if reorder {
-                                        // Update latest accept time seen
-                                        latest_packet_time = Some(match latest_packet_time {
-                                            Some(t) => std::cmp::max(t, msg.accept_time),
-                                            None => msg.accept_time,
-                                        });
-
-                                        message_buffer.push(Reverse(msg));
-                                        
-                                        // Process messages that are ready (older than 3 seconds from latest accept time)
-                                        if let Some(latest_time) = latest_packet_time {
-                                            while let Some(Reverse(msg)) = message_buffer.peek() {
-                                                if latest_time.duration_since(msg.accept_time).unwrap() > Duration::from_secs(3) {
-                                                    if let Some(Reverse(msg)) = message_buffer.pop() {
-                                                        output_message(&msg, output_format);
-                                                    }
-                                                } else {
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    } else {
-                                        // If not reordering, output messages immediately
-                                        output_message(&msg, output_format);
-                                    }
-                                }
+        let packet_time = SystemTime::UNIX_EPOCH +
+            Duration::from_secs(packet.header.ts.tv_sec as u64) +
+            Duration::from_micros(packet.header.ts.tv_usec as u64);
+        process_packet(packet.data, packet_time, config, &mut state, &mut out);
+    }
+
+    finish_capture(config, &mut state, &mut out, live);
+}
+
+/// Reads `path` through a memory map and walks its PCAP records without
+/// copying packet payloads, avoiding the per-packet allocation churn of
+/// `pcap::Capture::next_packet`. Only little-endian, microsecond-resolution
+/// PCAP files (the common case for libpcap on this platform) are supported.
+fn run_capture_mmap(path: &str, config: &CaptureConfig) {
+    const GLOBAL_HEADER_LEN: usize = 24;
+    const RECORD_HEADER_LEN: usize = 16;
+    const PCAP_MAGIC_MICRO: u32 = 0xa1b2_c3d4;
+
+    let file = std::fs::File::open(path).expect("Failed to open pcap file for mmap");
+    let mmap = unsafe { Mmap::map(&file) }.expect("Failed to mmap pcap file");
+
+    if mmap.len() < GLOBAL_HEADER_LEN {
+        panic!("pcap file is too short to contain a global header");
+    }
+    let magic = u32::from_le_bytes(mmap[0..4].try_into().unwrap());
+    if magic != PCAP_MAGIC_MICRO {
+        panic!(
+            "--mmap only supports little-endian, microsecond-resolution pcap files (got magic {:#x})",
+            magic
+        );
+    }
+
+    let mut out = io::BufWriter::new(io::stdout());
+    let mut state = CaptureState::default();
+
+    if !config.summarize {
+        write_format_header(&mut out, config.output_format);
+    }
+
+    let mut offset = GLOBAL_HEADER_LEN;
+    while offset + RECORD_HEADER_LEN <= mmap.len() {
+        let ts_sec = u32::from_le_bytes(mmap[offset..offset + 4].try_into().unwrap());
+        let ts_usec = u32::from_le_bytes(mmap[offset + 4..offset + 8].try_into().unwrap());
+        let incl_len = u32::from_le_bytes(mmap[offset + 8..offset + 12].try_into().unwrap()) as usize;
+        offset += RECORD_HEADER_LEN;
+
+        if offset + incl_len > mmap.len() {
+            break; // truncated trailing record
+        }
+        // A slice straight into the mapped file; no per-packet copy.
+        let packet_data = &mmap[offset..offset + incl_len];
+        offset += incl_len;
+
+        let packet_time = SystemTime::UNIX_EPOCH +
+            Duration::from_secs(ts_sec as u64) +
+            Duration::from_micros(ts_usec as u64);
+        process_packet(packet_data, packet_time, config, &mut state, &mut out);
+    }
+
+    // An mmap'd file always reaches EOF, so drain the reorder buffer like any
+    // other offline replay.
+    finish_capture(config, &mut state, &mut out, false);
+}
+
+/// Outputs any remaining reordered messages (or the per-issue summary) and
+/// flushes. `live` gates the reorder drain: live feeds never end, so a prior
+/// loop exit there means the device errored rather than finished cleanly.
+fn finish_capture<W: Write>(config: &CaptureConfig, state: &mut CaptureState, out: &mut W, live: bool) {
+    if config.summarize {
+        print_issue_summary(&state.issue_stats);
+        out.flush().expect("Failed to flush output");
+        return;
+    }
+
+    if config.reorder && !live {
+        while let Some(Reverse(msg)) = state.message_buffer.pop() {
+            output_message(&msg, config.output_format, out);
+        }
+    }
+
+    out.flush().expect("Failed to flush output");
+}
+
+/// Parses one raw link-layer frame (Ethernet/IPv4) and dispatches its UDP or
+/// TCP payload to the quote parser, same as both capture paths use it.
+fn process_packet<W: Write>(
+    data: &[u8],
+    packet_time: SystemTime,
+    config: &CaptureConfig,
+    state: &mut CaptureState,
+    out: &mut W,
+) {
+    let ethernet = match EthernetPacket::new(data) {
+        Some(e) => e,
+        None => return,
+    };
+    if ethernet.get_ethertype() != EtherTypes::Ipv4 {
+        return;
+    }
+    let ipv4 = match Ipv4Packet::new(ethernet.payload()) {
+        Some(i) => i,
+        None => return,
+    };
+
+    match ipv4.get_next_level_protocol() {
+        IpNextHeaderProtocols::Udp => {
+            if let Some(udp_packet) = UdpPacket::new(ipv4.payload()) {
+                let dst_port = udp_packet.get_destination();
+                if config.ports.contains(&dst_port) {
+                    if let Some(msg) = parse_quote_message(udp_packet.payload(), packet_time) {
+                        handle_quote_message(msg, config, state, out);
+                    }
+                }
+            }
+        }
+        IpNextHeaderProtocols::Tcp => {
+            if let Some(tcp_packet) = TcpPacket::new(ipv4.payload()) {
+                let dst_port = tcp_packet.get_destination();
+                if config.ports.contains(&dst_port) {
+                    let payload = tcp_packet.payload();
+                    if !payload.is_empty() {
+                        // Reassemble this connection's contiguous byte stream
+                        // before handing it to the same framing decoder the
+                        // async-stream path (QuoteDecoder) uses.
+                        let flow_key = (
+                            ipv4.get_source(),
+                            tcp_packet.get_source(),
+                            ipv4.get_destination(),
+                            dst_port,
+                        );
+
+                        if !state.tcp_streams.contains_key(&flow_key)
+                            && state.tcp_streams.len() >= MAX_TCP_STREAM_FLOWS
+                        {
+                            // Flow table is full of *other* flows; evict the
+                            // least-recently-touched one rather than
+                            // refusing to track this new connection, so a
+                            // long-running capture keeps room for fresh
+                            // 4-tuples instead of wedging permanently.
+                            evict_oldest_tcp_flow(&mut state.tcp_streams);
+                        }
+
+                        state.tcp_stream_clock += 1;
+                        let touched_at = state.tcp_stream_clock;
+
+                        let mut decoded = Vec::new();
+                        {
+                            let flow = state.tcp_streams.entry(flow_key).or_default();
+                            flow.last_touched = touched_at;
+                            flow.data.extend_from_slice(payload);
+
+                            let mut decoder = QuoteDecoder::with_packet_time(packet_time);
+                            while let Ok(Some(msg)) = decoder.decode(&mut flow.data) {
+                                decoded.push(msg);
                             }
+
+                            // A marker can be stuck waiting on a frame that
+                            // never completes (a stalled or malformed
+                            // connection); bound the backlog instead of
+                            // buffering it forever. Left in place (not
+                            // removed) so a steady-state flow keeps its
+                            // already-allocated buffer across packets.
+                            if flow.data.len() > MAX_TCP_STREAM_BUFFER {
+                                flow.data.clear();
+                            }
+                        }
+                        for msg in decoded {
+                            handle_quote_message(msg, config, state, out);
                         }
                     }
                 }
             }
         }
+        _ => {}
     }
+}
 
-    // Output remaining messages in the buffer if reordering was enabled
-    if reorder {
-        while let Some(Reverse(msg)) = message_buffer.pop() {
-            output_message(&msg, output_format);
+fn handle_quote_message<W: Write>(
+    msg: QuoteMessage,
+    config: &CaptureConfig,
+    state: &mut CaptureState,
+    out: &mut W,
+) {
+    if config.summarize {
+        state
+            .issue_stats
+            .entry(msg.issue_code.clone())
+            .and_modify(|stats| stats.update(&msg))
+            .or_insert_with(|| IssueStats::new(&msg));
+    } else if config.reorder {
+        // Update latest accept time seen
+        state.latest_packet_time = Some(match state.latest_packet_time {
+            Some(t) => std::cmp::max(t, msg.accept_time),
+            None => msg.accept_time,
+        });
+
+        state.message_buffer.push(Reverse(msg));
+
+        // Process messages that are ready (older than the reorder window from the latest accept time)
+        if let Some(latest_time) = state.latest_packet_time {
+            while let Some(Reverse(msg)) = state.message_buffer.peek() {
+                if latest_time.duration_since(msg.accept_time).unwrap() > config.reorder_window {
+                    if let Some(Reverse(msg)) = state.message_buffer.pop() {
+                        output_message(&msg, config.output_format, out);
+                    }
+                } else {
+                    break;
+                }
+            }
         }
+    } else {
+        // If not reordering, output messages immediately
+        output_message(&msg, config.output_format, out);
     }
 }
 
-fn output_message(msg: &QuoteMessage, format: OutputFormat) {
+fn output_message<W: Write>(msg: &QuoteMessage, format: OutputFormat, out: &mut W) {
     match format {
         OutputFormat::Default => {
-            let mut output = format!("{} {} {}", 
+            let mut output = format!("{} {} {}",
                 format_system_time(msg.packet_time),
                 format_system_time(msg.accept_time),
                 msg.issue_code.trim()
             );
-            
+
             // Add bids (from highest to lowest)
             for &(qty, price) in msg.bids.iter().rev() {
                 output.push_str(&format!(" {}@{}", qty, price));
             }
-            
+
             // Add asks (from lowest to highest)
             for &(qty, price) in msg.asks.iter() {
                 output.push_str(&format!(" {}@{}", qty, price));
             }
-            
-            println!("{}", output);
+
+            writeln!(out, "{}", output).expect("Failed to write quote");
         }
         OutputFormat::Formatted => {
-            println!("Packet-Time: {} | Accept-Time: {} | Issue-Code: {}", 
+            write!(out, "Packet-Time: {} | Accept-Time: {} | Issue-Code: {}\nBids: ",
                 format_system_time(msg.packet_time),
                 format_system_time(msg.accept_time),
                 msg.issue_code.trim()
-            );
-            
-            print!("Bids: ");
+            ).expect("Failed to write quote");
+
             for (i, &(qty, price)) in msg.bids.iter().rev().enumerate() {
                 if i > 0 {
-                    print!(", ");
+                    write!(out, ", ").expect("Failed to write quote");
                 }
-                print!("{}@{}", qty, price);
+                write!(out, "{}@{}", qty, price).expect("Failed to write quote");
             }
-            
-            print!(" | Asks: ");
+
+            write!(out, " | Asks: ").expect("Failed to write quote");
             for (i, &(qty, price)) in msg.asks.iter().enumerate() {
                 if i > 0 {
-                    print!(", ");
+                    write!(out, ", ").expect("Failed to write quote");
                 }
-                print!("{}@{}", qty, price);
+                write!(out, "{}@{}", qty, price).expect("Failed to write quote");
             }
-            println!();
+            writeln!(out).expect("Failed to write quote");
+        }
+        OutputFormat::Binary => {
+            write_binary_message(out, msg).expect("Failed to write SBE record");
+        }
+        OutputFormat::Csv => {
+            write_csv_row(out, msg).expect("Failed to write CSV row");
         }
     }
 }
@@ -323,3 +898,180 @@ impl Ord for QuoteMessage {
         self.accept_time.cmp(&other.accept_time)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds one well-formed 214-byte B6034 frame (marker through accept
+    /// time) from `bids`/`asks` given in on-the-wire order (price first,
+    /// then quantity), matching `parse_quote_fields`'s field layout.
+    fn build_quote_frame(
+        issue_code: &str,
+        bids: &[(u64, u64)],
+        asks: &[(u64, u64)],
+        accept_time: &str,
+    ) -> Vec<u8> {
+        assert_eq!(bids.len(), 5);
+        assert_eq!(asks.len(), 5);
+        assert_eq!(accept_time.len(), 8);
+
+        let mut frame = Vec::new();
+        frame.extend_from_slice(b"B6034");
+
+        let mut code = issue_code.as_bytes().to_vec();
+        code.resize(12, b' ');
+        frame.extend_from_slice(&code);
+
+        frame.extend_from_slice(&[b'0'; 3 + 2 + 7]); // issue seq no, market status, total bid qty volume
+
+        for &(price, qty) in bids {
+            frame.extend_from_slice(format!("{:05}", price).as_bytes());
+            frame.extend_from_slice(format!("{:07}", qty).as_bytes());
+        }
+
+        frame.extend_from_slice(&[b'0'; 7]); // total ask qty volume
+
+        for &(price, qty) in asks {
+            frame.extend_from_slice(format!("{:05}", price).as_bytes());
+            frame.extend_from_slice(format!("{:07}", qty).as_bytes());
+        }
+
+        frame.extend_from_slice(&[b'0'; 5 + 4 * 5 + 5 + 4 * 5]); // best bid/ask valid quote + counts
+        frame.extend_from_slice(accept_time.as_bytes());
+
+        assert_eq!(frame.len(), QUOTE_MESSAGE_LEN);
+        frame
+    }
+
+    fn sample_frame() -> Vec<u8> {
+        build_quote_frame(
+            "005930",
+            &[(100, 1), (101, 2), (102, 3), (103, 4), (104, 5)],
+            &[(200, 6), (201, 7), (202, 8), (203, 9), (204, 10)],
+            "09300000",
+        )
+    }
+
+    #[test]
+    fn decode_handles_split_reads() {
+        let frame = sample_frame();
+        let mut buf = BytesMut::new();
+        let mut decoder = QuoteDecoder::default();
+
+        buf.extend_from_slice(&frame[..frame.len() / 2]);
+        assert!(decoder.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(&frame[frame.len() / 2..]);
+        let msg = decoder
+            .decode(&mut buf)
+            .unwrap()
+            .expect("frame should decode once fully buffered");
+
+        assert_eq!(msg.issue_code.trim(), "005930");
+        assert_eq!(msg.bids, vec![(1, 100), (2, 101), (3, 102), (4, 103), (5, 104)]);
+        assert_eq!(msg.asks, vec![(6, 200), (7, 201), (8, 202), (9, 203), (10, 204)]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_skips_garbage_before_marker() {
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(b"this is not a quote message at all");
+        buf.extend_from_slice(&sample_frame());
+
+        let msg = QuoteDecoder::default()
+            .decode(&mut buf)
+            .unwrap()
+            .expect("decoder should find the marker past the leading garbage");
+
+        assert_eq!(msg.issue_code.trim(), "005930");
+    }
+
+    #[test]
+    fn decode_waits_for_a_frame_that_never_completes() {
+        let frame = sample_frame();
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&frame[..frame.len() - 1]);
+
+        let result = QuoteDecoder::default().decode(&mut buf).unwrap();
+
+        assert!(result.is_none());
+        assert_eq!(buf.len(), frame.len() - 1);
+    }
+
+    #[test]
+    fn write_binary_message_round_trips_known_fields() {
+        let msg = QuoteMessage {
+            packet_time: UNIX_EPOCH + Duration::from_micros(1_000_000),
+            accept_time: UNIX_EPOCH + Duration::from_micros(2_000_000),
+            issue_code: "005930".to_string(),
+            bids: vec![(1, 10), (2, 20), (3, 30), (4, 40), (5, 50)],
+            asks: vec![(6, 60), (7, 70), (8, 80), (9, 90), (10, 100)],
+        };
+
+        let mut out = Vec::new();
+        write_binary_message(&mut out, &msg).unwrap();
+        assert_eq!(out.len(), SBE_RECORD_SIZE as usize);
+
+        assert_eq!(&out[0..12], b"005930      ");
+        assert_eq!(u64::from_le_bytes(out[12..20].try_into().unwrap()), 1_000_000);
+        assert_eq!(u64::from_le_bytes(out[20..28].try_into().unwrap()), 2_000_000);
+
+        // Bids are written highest-first, i.e. in reverse of storage order.
+        assert_eq!(u64::from_le_bytes(out[28..36].try_into().unwrap()), 5);
+        assert_eq!(u64::from_le_bytes(out[36..44].try_into().unwrap()), 50);
+
+        // Asks are written in storage order (lowest ask first).
+        let asks_start = 28 + 5 * 16;
+        assert_eq!(
+            u64::from_le_bytes(out[asks_start..asks_start + 8].try_into().unwrap()),
+            6
+        );
+        assert_eq!(
+            u64::from_le_bytes(out[asks_start + 8..asks_start + 16].try_into().unwrap()),
+            60
+        );
+    }
+
+    #[test]
+    fn write_csv_row_orders_and_counts_columns() {
+        let msg = QuoteMessage {
+            packet_time: UNIX_EPOCH,
+            accept_time: UNIX_EPOCH,
+            issue_code: "005930".to_string(),
+            bids: vec![(1, 10), (2, 20), (3, 30), (4, 40), (5, 50)],
+            asks: vec![(6, 60), (7, 70), (8, 80), (9, 90), (10, 100)],
+        };
+
+        let mut out = Vec::new();
+        write_csv_row(&mut out, &msg).unwrap();
+        let row = String::from_utf8(out).unwrap();
+        let columns: Vec<&str> = row.trim_end().split(',').collect();
+
+        assert_eq!(columns.len(), 3 + 5 * 2 + 5 * 2);
+        assert_eq!(columns[2], "005930");
+        // Highest bid (bids.last()) comes first, matching the text formatter.
+        assert_eq!(&columns[3..5], ["5", "50"]);
+        assert_eq!(&columns[13..15], ["6", "60"]);
+    }
+
+    #[test]
+    fn write_csv_row_zero_fills_missing_levels() {
+        let msg = QuoteMessage {
+            packet_time: UNIX_EPOCH,
+            accept_time: UNIX_EPOCH,
+            issue_code: "005930".to_string(),
+            bids: vec![],
+            asks: vec![],
+        };
+
+        let mut out = Vec::new();
+        write_csv_row(&mut out, &msg).unwrap();
+        let row = String::from_utf8(out).unwrap();
+        let columns: Vec<&str> = row.trim_end().split(',').collect();
+
+        assert_eq!(columns.len(), 3 + 5 * 2 + 5 * 2);
+        assert!(columns[3..].iter().all(|&c| c == "0"));
+    }
+}